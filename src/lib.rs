@@ -1,13 +1,44 @@
 pub mod shmem;
 pub mod log;
+pub mod thread_state;
+pub mod telemetry;
+pub mod proctree;
 
-use crate::shmem::{alloc_slot, release_slot, StallTracker, ThreadHint, GIL};
-use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use crate::shmem::{
+    alloc_slot, current_os_thread_id, list_slots, release_slot, StallTracker, ThreadHint,
+    ThreadHintKind, GIL,
+};
+use pyo3::exceptions::{PyRuntimeError, PyStopIteration, PyValueError};
 use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::sync::Mutex;
+
+/// Nesting state for activate()/deactivate(). `net` is a running total of
+/// activate() calls (+1 each) minus deactivate() calls (-1 each); it's back at the
+/// baseline (zero) exactly when every opened region has been closed again, whether a
+/// given region was opened by activate() (as the tracker's own `__enter__` does, to
+/// go active first) or by deactivate() (as `released_scope()`/`allow_threads()` do,
+/// to go idle first -- a fresh tracker starts active, so they have to be able to
+/// open with deactivate() directly). Only the call that first moves `net` away from
+/// zero touches the tracker, and only after checking whether it's already in the
+/// desired state; `toggled_on_entry` remembers whether that call had to toggle, so
+/// the call that brings `net` back to zero can undo exactly that.
+///
+/// Nested regions of the *same* kind (e.g. two nested `released_scope()`s) compose
+/// correctly. Mixing kinds within a single nesting chain (e.g. a bare `activate()`
+/// region with a `released_scope()` opened inside it) isn't a supported pattern --
+/// same as the original reentrant design, callers are expected to pair each
+/// activate() with a deactivate() (or vice versa) at matching nesting levels.
+#[derive(Default)]
+struct Nesting {
+    net: isize,
+    toggled_on_entry: bool,
+}
 
 #[pyclass(name = "StallTracker", module = "perpetuo")]
 struct PyStallTracker {
     stall_tracker: Option<&'static mut StallTracker>,
+    nesting: Mutex<Nesting>,
 }
 
 #[derive(FromPyObject)]
@@ -16,6 +47,8 @@ enum ThreadHintArg {
     String(String),
     #[pyo3(transparent, annotation = "int")]
     Int(usize),
+    /// `("gil", interp_id)`: a GIL hint scoped to one sub-interpreter.
+    InterpreterGil(String, usize),
 }
 
 impl ThreadHintArg {
@@ -24,14 +57,38 @@ impl ThreadHintArg {
             ThreadHintArg::String(s) => {
                 if s == "gil" {
                     Ok(GIL)
+                } else if s == "asyncio" {
+                    // An event loop is thread-affine, so tracking it is really just
+                    // tracking whichever thread creates this tracker: this alias is
+                    // sugar for `ThreadHint::from_thread_id(current_os_thread_id())`,
+                    // nothing more. There's no Python source in this crate, so the
+                    // actual event-loop instrumentation -- constructing the
+                    // `StallTracker` from inside the loop's own thread, then wrapping
+                    // `loop._run_once` (or the selector wait) to call
+                    // go_active()/go_idle() around each iteration -- has to live in a
+                    // separate Python-level installer that calls into this binding;
+                    // it isn't implemented here.
+                    ThreadHint::from_thread_id(current_os_thread_id())
+                        .map_err(|err| PyRuntimeError::new_err(err.to_string()))
                 } else {
-                    Err(PyValueError::new_err("must be integer or the string 'gil'"))
+                    Err(PyValueError::new_err(
+                        "must be an integer, the string 'gil' or 'asyncio', or ('gil', interp_id)",
+                    ))
                 }
             }
             ThreadHintArg::Int(i) => match ThreadHint::from_thread_id(*i) {
                 Ok(thread_hint) => Ok(thread_hint),
                 Err(rust_err) => Err(PyValueError::new_err(rust_err.to_string())),
             },
+            ThreadHintArg::InterpreterGil(tag, interp_id) => {
+                if tag == "gil" {
+                    Ok(ThreadHint::for_interpreter(*interp_id))
+                } else {
+                    Err(PyValueError::new_err(
+                        "first element of a tuple thread hint must be 'gil'",
+                    ))
+                }
+            }
         }
     }
 }
@@ -42,6 +99,33 @@ fn rustify(py: &PyStallTracker) -> PyResult<&&mut StallTracker> {
         .ok_or_else(|| PyRuntimeError::new_err("attempt to use closed StallTracker"))
 }
 
+impl PyStallTracker {
+    /// Shared implementation of `activate()`/`deactivate()`. `want_active` is the
+    /// state this call wants while its region is open -- `true` for `activate()`,
+    /// `false` for `deactivate()`. See `Nesting` for the reasoning. Not a `#[pymethods]`
+    /// fn, since it's an implementation detail, not part of the Python-facing API.
+    fn enter_or_leave(&self, want_active: bool) -> PyResult<()> {
+        let stall_tracker = rustify(self)?;
+        let mut nesting = self.nesting.lock().unwrap();
+        let previous_net = nesting.net;
+        nesting.net += if want_active { 1 } else { -1 };
+        if previous_net == 0 {
+            // Opening the outermost region: drive toward the target state, and
+            // remember whether that took a toggle.
+            nesting.toggled_on_entry = stall_tracker.is_active() != want_active;
+            if nesting.toggled_on_entry {
+                stall_tracker.toggle();
+            }
+        } else if nesting.net == 0 {
+            // Closing the outermost region: undo whatever the opening call did.
+            if nesting.toggled_on_entry {
+                stall_tracker.toggle();
+            }
+        }
+        Ok(())
+    }
+}
+
 #[pymethods]
 impl PyStallTracker {
     #[new]
@@ -52,6 +136,7 @@ impl PyStallTracker {
         };
         Ok(PyStallTracker {
             stall_tracker: Some(stall_tracker),
+            nesting: Mutex::new(Nesting::default()),
         })
     }
 
@@ -92,6 +177,102 @@ impl PyStallTracker {
         }
         Ok(())
     }
+
+    /// Returns a context manager that marks this tracker idle for the duration of
+    /// the `with` block, and on exit (including via exception) restores the state it
+    /// had before -- active, for a tracker that was active.
+    ///
+    /// Meant for wrapping code that's known to release the GIL internally -- e.g. a
+    /// `Python::allow_threads` call on the Rust side, or a blocking call like
+    /// `socket.recv` from pure Python -- so that a thread cooperatively yielding the
+    /// GIL isn't mistaken for one that's stalled holding it.
+    fn released_scope(slf: PyRef<'_, Self>) -> PyReleasedScope {
+        PyReleasedScope {
+            tracker: Py::from(slf),
+        }
+    }
+
+    /// Like `go_active`, but reentrant and idempotent: ensures the tracker is active,
+    /// nested/repeated calls just bump a depth counter, and only the outermost call
+    /// that actually needed to change anything toggles the tracker. Pair with
+    /// `deactivate()` -- which may be called either to close this region, or, on a
+    /// fresh tracker, as the opening call itself (that's what `released_scope()`/
+    /// `allow_threads()` do, since they want idle first).
+    fn activate(&self) -> PyResult<()> {
+        self.enter_or_leave(true)
+    }
+
+    /// Like `go_idle`, but reentrant and idempotent: ensures the tracker is idle.
+    /// Unlike the raw `go_idle`, this doesn't raise on a tracker that's already idle.
+    /// It can open a region on a fresh tracker (which starts out active) -- the
+    /// matching `activate()` call, however many levels up, restores whatever state
+    /// the tracker was actually in before this nested region opened.
+    fn deactivate(&self) -> PyResult<()> {
+        self.enter_or_leave(false)
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyResult<PyRef<'_, Self>> {
+        slf.activate()?;
+        Ok(slf)
+    }
+
+    fn __exit__(
+        &self,
+        _exc_type: &PyAny,
+        _exc_value: &PyAny,
+        _traceback: &PyAny,
+    ) -> PyResult<bool> {
+        self.deactivate()?;
+        Ok(false)
+    }
+
+    /// Async equivalent of `__enter__`. activate()/deactivate() are just atomic ops,
+    /// not real asynchronous work, so this returns an awaitable that resolves
+    /// immediately rather than pulling in an async runtime dependency.
+    fn __aenter__(slf: PyRef<'_, Self>, py: Python) -> PyResult<PyImmediate> {
+        slf.activate()?;
+        Ok(PyImmediate {
+            value: Some(slf.into_py(py)),
+        })
+    }
+
+    fn __aexit__(
+        &self,
+        py: Python,
+        _exc_type: &PyAny,
+        _exc_value: &PyAny,
+        _traceback: &PyAny,
+    ) -> PyResult<PyImmediate> {
+        self.deactivate()?;
+        Ok(PyImmediate {
+            value: Some(false.into_py(py)),
+        })
+    }
+}
+
+/// A trivial already-resolved awaitable, for implementing `__aenter__`/`__aexit__`
+/// without depending on an async runtime: Python's `await` protocol drives
+/// `__await__()` with `next()` and treats an immediate `StopIteration(value)` as the
+/// awaited result, with no actual suspension.
+#[pyclass(module = "perpetuo")]
+struct PyImmediate {
+    value: Option<PyObject>,
+}
+
+#[pymethods]
+impl PyImmediate {
+    fn __await__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python) -> PyResult<()> {
+        let value = self.value.take().unwrap_or_else(|| py.None());
+        Err(PyStopIteration::new_err(value))
+    }
 }
 
 impl Drop for PyStallTracker {
@@ -102,15 +283,192 @@ impl Drop for PyStallTracker {
     }
 }
 
+/// Context manager returned by `StallTracker.released_scope()`.
+#[pyclass(name = "_ReleasedScope", module = "perpetuo")]
+struct PyReleasedScope {
+    tracker: Py<PyStallTracker>,
+}
+
+#[pymethods]
+impl PyReleasedScope {
+    fn __enter__(&self, py: Python) -> PyResult<()> {
+        // A released scope's "active" span is idle from the tracker's point of view,
+        // so entering it is a deactivate() -- this shares the same depth counter as
+        // activate()/deactivate()/__enter__/__exit__, so released_scope() nests
+        // correctly with itself. Opened inside a region of the tracker's own context
+        // manager, it closes that region's count instead and doesn't mark the tracker
+        // idle (see `Nesting`).
+        self.tracker.borrow(py).deactivate()
+    }
+
+    fn __exit__(
+        &self,
+        py: Python,
+        _exc_type: &PyAny,
+        _exc_value: &PyAny,
+        _traceback: &PyAny,
+    ) -> PyResult<bool> {
+        self.tracker.borrow(py).activate()?;
+        // Don't suppress whatever exception (if any) triggered the exit.
+        Ok(false)
+    }
+}
+
 /// Same as time.sleep, but it holds the GIL. Useful for testing.
 #[pyfunction]
 fn stall_gil(seconds: f64) {
     std::thread::sleep(std::time::Duration::from_secs_f64(seconds));
 }
 
+/// Run `callable`, marking `tracker` idle for the duration of the call and restoring
+/// its previous state (active, for a tracker that was active) once it returns.
+///
+/// Equivalent to `with tracker.released_scope(): callable()`, provided as a
+/// convenience for the common single-call case. Note that `callable` is a Python
+/// callable, so it needs the GIL to run at all -- there's no actual
+/// `Python::allow_threads` here, just the bookkeeping that lets a thread
+/// cooperatively yielding the GIL inside `callable` (e.g. a blocking call like
+/// `socket.recv`) avoid being mistaken for a stall.
+///
+/// Goes through the same reentrant activate()/deactivate() accounting as
+/// `released_scope()`, so nesting `allow_threads()` calls (or mixing them with
+/// `released_scope()`/the tracker's own context manager) on the same tracker doesn't
+/// raise "Already idle"/"Already active".
+#[pyfunction]
+fn allow_threads(py: Python, tracker: Py<PyStallTracker>, callable: PyObject) -> PyResult<PyObject> {
+    tracker.borrow(py).deactivate()?;
+    let result = callable.call0(py);
+    tracker.borrow(py).activate()?;
+    result
+}
+
+fn thread_hint_to_py(py: Python, hint: ThreadHint) -> PyObject {
+    match hint.describe() {
+        ThreadHintKind::Gil => "gil".into_py(py),
+        ThreadHintKind::Thread(tid) => tid.into_py(py),
+        ThreadHintKind::InterpreterGil(interp_id) => ("gil", interp_id).into_py(py),
+    }
+}
+
+/// List every currently-allocated stall tracker in this process, as a list of dicts
+/// with keys `name`, `thread_hint`, `is_active`, `count`, and `counter_address`.
+///
+/// This is an in-process alternative to the out-of-process watchdog -- handy for a
+/// `/debug` endpoint, a SIGQUIT handler that logs a snapshot of all trackers, or
+/// tests/health checks that want to assert on aggregate tracker state.
+#[pyfunction]
+fn list_trackers(py: Python) -> PyResult<Vec<PyObject>> {
+    list_slots()
+        .into_iter()
+        .map(|info| {
+            let dict = PyDict::new(py);
+            dict.set_item("name", info.name)?;
+            dict.set_item("thread_hint", thread_hint_to_py(py, info.thread_hint))?;
+            dict.set_item("is_active", info.is_active)?;
+            dict.set_item("count", info.count)?;
+            dict.set_item("counter_address", info.counter_address)?;
+            Ok(dict.into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_tracker(name: &str) -> PyStallTracker {
+        PyStallTracker::new(name, ThreadHintArg::String("gil".to_string())).unwrap()
+    }
+
+    #[test]
+    fn activate_on_an_already_active_tracker_is_a_no_op() {
+        let tracker = new_tracker("test-activate-noop");
+        // alloc_slot leaves a freshly allocated tracker active, so activate() -- which
+        // wants active -- has nothing to do.
+        assert!(tracker.is_active().unwrap());
+
+        tracker.activate().unwrap();
+        assert!(tracker.is_active().unwrap(), "activate() on an active tracker must not toggle");
+
+        tracker.deactivate().unwrap();
+        assert!(tracker.is_active().unwrap(), "matching deactivate() must undo exactly nothing");
+    }
+
+    #[test]
+    fn nested_activate_only_toggles_at_the_outermost_level() {
+        let tracker = new_tracker("test-activate-nesting");
+        tracker.deactivate().unwrap();
+        assert!(!tracker.is_active().unwrap(), "outermost deactivate() toggles to idle");
+
+        tracker.deactivate().unwrap();
+        assert!(!tracker.is_active().unwrap(), "nested deactivate() doesn't toggle");
+
+        tracker.activate().unwrap();
+        assert!(!tracker.is_active().unwrap(), "activate() closing the nested region doesn't toggle");
+
+        tracker.activate().unwrap();
+        assert!(tracker.is_active().unwrap(), "activate() closing the outermost region toggles back to active");
+    }
+
+    #[test]
+    fn deactivate_on_a_fresh_tracker_marks_it_idle() {
+        let tracker = new_tracker("test-deactivate-first");
+        // alloc_slot leaves a freshly allocated tracker active; deactivate() as the
+        // very first call (as released_scope()/allow_threads() do) must succeed and
+        // mark it idle.
+        assert!(tracker.is_active().unwrap());
+        tracker.deactivate().unwrap();
+        assert!(!tracker.is_active().unwrap());
+
+        tracker.activate().unwrap();
+        assert!(tracker.is_active().unwrap(), "matching activate() restores the original state");
+    }
+
+    #[test]
+    fn released_scope_on_a_fresh_tracker_goes_idle_then_active() {
+        Python::with_gil(|py| {
+            let tracker = Py::new(py, new_tracker("test-released-scope")).unwrap();
+            assert!(tracker.borrow(py).is_active().unwrap());
+
+            let scope = PyStallTracker::released_scope(tracker.borrow(py));
+            scope.__enter__(py).unwrap();
+            assert!(!tracker.borrow(py).is_active().unwrap(), "released_scope() marks the tracker idle");
+
+            let none = py.None();
+            let suppress = scope
+                .__exit__(py, none.as_ref(py), none.as_ref(py), none.as_ref(py))
+                .unwrap();
+            assert!(!suppress);
+            assert!(tracker.borrow(py).is_active().unwrap(), "exiting released_scope() restores active");
+        });
+    }
+
+    #[test]
+    fn allow_threads_on_a_fresh_tracker_goes_idle_then_active() {
+        Python::with_gil(|py| {
+            let tracker = Py::new(py, new_tracker("test-allow-threads")).unwrap();
+            assert!(tracker.borrow(py).is_active().unwrap());
+
+            // A no-op Python callable; what matters is the tracker's state transitions
+            // around the call, not anything the callable itself does.
+            let callable = py.eval("lambda: None", None, None).unwrap().into_py(py);
+
+            allow_threads(py, tracker.clone_ref(py), callable).unwrap();
+            assert!(
+                tracker.borrow(py).is_active().unwrap(),
+                "allow_threads() restores active once the callable returns"
+            );
+        });
+    }
+}
+
 #[pymodule]
 fn _perpetuo(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<PyStallTracker>()?;
+    m.add_class::<PyReleasedScope>()?;
+    m.add_class::<PyImmediate>()?;
     m.add_function(wrap_pyfunction!(stall_gil, m)?)?;
+    m.add_function(wrap_pyfunction!(allow_threads, m)?)?;
+    m.add_function(wrap_pyfunction!(list_trackers, m)?)?;
     Ok(())
 }
@@ -2,6 +2,8 @@ use py_spy::StackTrace;
 use serde::Serialize;
 use std::collections::HashMap;
 
+use crate::thread_state::ThreadState;
+
 #[derive(Serialize, Debug, Clone, Copy)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum Severity {
@@ -34,6 +36,31 @@ pub struct StallDetails {
     pub other_traces: Vec<Vec<StackFrame>>,
     pub cmdline: Vec<String>,
     pub rate_limited: bool,
+    /// OS scheduler state of the thread responsible for the stall, if known. See
+    /// `thread_state::ThreadState` for what each variant means.
+    pub thread_state: Option<ThreadState>,
+    /// I/O and CPU usage accrued by the process over the stall window, if telemetry
+    /// collection was enabled (see `--collect-telemetry`).
+    pub io_delta: Option<IoDelta>,
+}
+
+#[derive(Serialize)]
+pub struct IoDelta {
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub cpu_time_ms: f64,
+    pub rss_delta_bytes: i64,
+}
+
+impl From<&crate::telemetry::TelemetryDelta> for IoDelta {
+    fn from(delta: &crate::telemetry::TelemetryDelta) -> Self {
+        IoDelta {
+            read_bytes: delta.read_bytes,
+            write_bytes: delta.write_bytes,
+            cpu_time_ms: delta.cpu_time.as_secs_f64() * 1000.0,
+            rss_delta_bytes: delta.rss_bytes,
+        }
+    }
 }
 
 pub fn convert_stack(trace: &StackTrace) -> Vec<StackFrame> {
@@ -120,6 +147,15 @@ pub fn log_with_details(
         if let Some(details) = stall_details {
             eprintln!("  Duration (so far): {} ms", details.length_ms);
             eprintln!("  Command line: {}", details.cmdline.join(" "));
+            if let Some(thread_state) = details.thread_state {
+                eprintln!("  Responsible thread's OS state: {:?}", thread_state);
+            }
+            if let Some(io) = &details.io_delta {
+                eprintln!(
+                    "  During this stall: {} bytes read, {} bytes written, {:.1}ms CPU, {:+} bytes RSS",
+                    io.read_bytes, io.write_bytes, io.cpu_time_ms, io.rss_delta_bytes
+                );
+            }
             if details.rate_limited {
                 eprintln!("  -- (no traceback because of rate limiting) --");
             }
@@ -6,7 +6,9 @@ use clap::{ArgAction, Parser, Subcommand};
 use indoc::indoc;
 
 use perpetuo::shmem::PerpetuoProc;
-use perpetuo::log::{log, dump_stacktrace, Severity};
+use perpetuo::log::{log, log_with_details, convert_stack, IoDelta, Severity, StallDetails};
+use perpetuo::proctree;
+use perpetuo::thread_state;
 
 #[derive(Parser, Debug)]
 #[command(about = "A stall tracker for Python", long_about = None)]
@@ -16,6 +18,10 @@ struct Cli {
     /// How often we inspect the target process to check for progress.
     #[arg(long, value_name = "SECONDS", default_value = "0.05", value_parser=parse_duration)]
     poll_interval: Duration,
+    /// With --follow-children, how often we re-scan for new/exited descendant
+    /// processes. Irrelevant without --follow-children.
+    #[arg(long, value_name = "SECONDS", default_value = "1.0", value_parser=parse_duration)]
+    discover_interval: Duration,
     /// How long a stall is required to trigger a traceback.
     ///
     /// We only alert if we issue two polls that both see the same stall and are at
@@ -58,19 +64,42 @@ enum Commands {
     /// Watch a given process, which must have set up at least one
     /// perpetuo.StallTracker.
     #[command(arg_required_else_help = true)]
-    Watch { pid: u32 },
+    Watch {
+        pid: u32,
+        /// Sample I/O and CPU usage (/proc/<pid>/io, /proc/<pid>/stat) at the start
+        /// and end of each stall, and report the deltas. Costs a few extra syscalls
+        /// per poll, so it's off by default to minimize interference with the
+        /// monitored process.
+        #[arg(long)]
+        collect_telemetry: bool,
+        /// Also watch every descendant process (children, grandchildren, ...),
+        /// discovered via /proc and re-scanned periodically (see
+        /// --discover-interval). Newly spawned children are picked up automatically;
+        /// exited processes are dropped. Useful for multiprocessing/gunicorn-style
+        /// workloads that fork workers.
+        ///
+        /// Linux-only for now: discovery walks /proc directly, and there's no macOS
+        /// or Windows equivalent implemented yet (unlike e.g. thread state sampling,
+        /// which does have a real macOS backend). On other platforms this flag logs
+        /// one warning and then watches only the given pid -- it does not fail the
+        /// whole `watch` invocation.
+        #[arg(long)]
+        follow_children: bool,
+    },
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Watch { pid } => watch_process(pid, &cli)?,
+        Commands::Watch { pid, collect_telemetry, follow_children } => {
+            watch_process(pid, collect_telemetry, follow_children, &cli)?
+        }
     }
     Ok(())
 }
 
-fn watch_process(pid: u32, cli: &Cli) -> Result<()> {
+fn make_config(cli: &Cli) -> py_spy::Config {
     let mut config = py_spy::Config::default();
     // We only collect a stack trace if we've already determined that the program is
     // misbehaving, so we're happy to pay some extra cost to get more detailed
@@ -83,18 +112,16 @@ fn watch_process(pid: u32, cli: &Cli) -> Result<()> {
         config.dump_locals = 0;
     }
     config.full_filenames = true;
-    let json_mode = cli.json_mode;
+    config
+}
 
+/// Attach to a single pid, with the friendly macOS/Linux permission-error messages.
+fn attach(pid: u32, collect_telemetry: bool, config: &py_spy::Config, json_mode: bool) -> Result<PerpetuoProc> {
     let mut additional_info = HashMap::new();
     additional_info.insert("pid".to_string(), pid.to_string());
-    log(Severity::Info, &format!("Attempting to monitor pid {pid}..."), Some(&additional_info), json_mode);
-    // let mut proc = loop {
-    //     if let Some(proc) = PerpetuoProc::new(pid, &config)? {
-    //         break proc;
-    //     }
-    //     std::thread::sleep(poll_interval);
-    // };
-    let result = PerpetuoProc::new(pid, &config);
+    log(Severity::Info, &format!("Attempting to monitor pid {pid}..."), &additional_info, json_mode);
+
+    let result = PerpetuoProc::new(pid, config, collect_telemetry);
     #[cfg(unix)]
     if let Err(err) = &result {
         if cfg!(target_os = "macos") && unsafe { libc::geteuid() } != 0 {
@@ -125,23 +152,73 @@ fn watch_process(pid: u32, cli: &Cli) -> Result<()> {
             );
         }
     }
-    let mut proc = result?;
-    log(Severity::Info, &format!("Successfully monitoring pid {pid}"), Some(&additional_info), json_mode);
+    let proc = result?;
+    log(Severity::Info, &format!("Successfully monitoring pid {pid}"), &additional_info, json_mode);
+    Ok(proc)
+}
+
+fn watch_process(pid: u32, collect_telemetry: bool, follow_children: bool, cli: &Cli) -> Result<()> {
+    let config = make_config(cli);
+    let json_mode = cli.json_mode;
+
+    let mut procs = HashMap::new();
+    procs.insert(pid, attach(pid, collect_telemetry, &config, json_mode)?);
+
     let mut next_traceback = Instant::now();
+    let mut next_discovery = Instant::now();
+    let mut warned_discovery_unsupported = false;
     loop {
         std::thread::sleep(cli.poll_interval);
-        if let Err(err) = check_once(
-            &mut proc,
-            &mut next_traceback,
-            cli.alert_interval,
-            cli.traceback_suppress,
-            json_mode,
-        ) {
-            if proc.spy.process.exe().is_err() {
-                log(Severity::Info, &format!("Process {} has exited", pid), Some(&additional_info), json_mode);
-                return Ok(());
+
+        if follow_children && Instant::now() >= next_discovery {
+            next_discovery = Instant::now() + cli.discover_interval;
+            match proctree::discover_descendants(pid) {
+                Ok(children) => {
+                    for child_pid in children {
+                        if let std::collections::hash_map::Entry::Vacant(entry) = procs.entry(child_pid) {
+                            if let Ok(child_proc) = attach(child_pid, collect_telemetry, &config, json_mode) {
+                                entry.insert(child_proc);
+                            }
+                        }
+                    }
+                }
+                Err(err) if !warned_discovery_unsupported => {
+                    warned_discovery_unsupported = true;
+                    log(
+                        Severity::Warning,
+                        &format!("--follow-children won't discover new descendants: {err}"),
+                        &HashMap::new(),
+                        json_mode,
+                    );
+                }
+                Err(_) => {}
+            }
+        }
+
+        let mut exited = Vec::new();
+        for (&watched_pid, proc) in procs.iter_mut() {
+            if let Err(err) = check_once(proc, &mut next_traceback, cli.alert_interval, cli.traceback_suppress, json_mode) {
+                if proc.spy.process.exe().is_err() {
+                    let mut additional_info = HashMap::new();
+                    additional_info.insert("pid".to_string(), watched_pid.to_string());
+                    log(Severity::Info, &format!("Process {watched_pid} has exited"), &additional_info, json_mode);
+                    exited.push(watched_pid);
+                } else if follow_children {
+                    // Don't let one misbehaving descendant take down the whole tree watch.
+                    let mut additional_info = HashMap::new();
+                    additional_info.insert("pid".to_string(), watched_pid.to_string());
+                    log(Severity::Warning, &format!("Error checking pid {watched_pid}, dropping it: {err}"), &additional_info, json_mode);
+                    exited.push(watched_pid);
+                } else {
+                    return Err(err);
+                }
             }
-            return Err(err);
+        }
+        for watched_pid in exited {
+            procs.remove(&watched_pid);
+        }
+        if procs.is_empty() {
+            return Ok(());
         }
     }
 }
@@ -168,7 +245,7 @@ fn check_once(
     traceback_interval: Duration,
     json_mode: bool,
 ) -> Result<()> {
-    for stall in proc.check_stalls(alert_interval)? {
+    for mut stall in proc.check_stalls(alert_interval)? {
         let mut additional_info = HashMap::new();
         additional_info.insert("name".to_string(), stall.name.to_string());
         additional_info.insert("pid".to_string(), proc.spy.process.pid.to_string());
@@ -176,40 +253,57 @@ fn check_once(
         log(
             Severity::Warning,
             &format!("{} stall detected in process {} for at least {:?}", stall.name, proc.spy.process.pid, stall.duration),
-            Some(&additional_info),
+            &additional_info,
             json_mode,
         );
         let now = Instant::now();
-        if now < *next_traceback {
-            log(Severity::Warning, &format!("No traceback due to rate-limiting for pid {}", proc.spy.process.pid), Some(&additional_info), json_mode);
-            continue;
-        }
-        *next_traceback = now + traceback_interval;
-        log(Severity::Info, &format!("command line: {:?}", proc.spy.process.cmdline()?), None, json_mode);
-        let traces = proc.spy.get_stack_traces()?;
-        let mut relevant = Vec::new();
-        let mut rest = Vec::new();
-        for trace in traces {
-            if stall.thread_hint.relevant(&trace) {
-                relevant.push(trace);
-            } else {
-                rest.push(trace);
-            }
+        let rate_limited = now < *next_traceback;
+        if rate_limited {
+            log(Severity::Warning, &format!("No traceback due to rate-limiting for pid {}", proc.spy.process.pid), &additional_info, json_mode);
+        } else {
+            *next_traceback = now + traceback_interval;
         }
-        if !relevant.is_empty() {
-            log(Severity::Warning, "This thread is probably responsible:", Some(&additional_info), json_mode);
-            for trace in &relevant {
-                dump_stacktrace(trace, json_mode);
+        let cmdline = proc.spy.process.cmdline()?;
+        log(Severity::Info, &format!("command line: {:?}", cmdline), &HashMap::new(), json_mode);
+
+        let mut relevant_traces = Vec::new();
+        let mut other_traces = Vec::new();
+        if !rate_limited {
+            let traces = proc.spy.get_stack_traces()?;
+            for trace in traces {
+                if stall.thread_hint.relevant(&trace) {
+                    if stall.thread_state.is_none() {
+                        stall.thread_state = thread_state::read(proc.spy.process.pid, trace.thread_id).ok();
+                    }
+                    relevant_traces.push(convert_stack(&trace));
+                } else {
+                    other_traces.push(convert_stack(&trace));
+                }
             }
-        }
-        if !rest.is_empty() {
-            if !relevant.is_empty() {
-                log(Severity::Info, "Other threads (probably not responsible):", Some(&additional_info), json_mode);
+            if !relevant_traces.is_empty() {
+                log(Severity::Warning, "This thread is probably responsible:", &additional_info, json_mode);
             }
-            for trace in &rest {
-                dump_stacktrace(trace, json_mode);
+            if !other_traces.is_empty() && !relevant_traces.is_empty() {
+                log(Severity::Info, "Other threads (probably not responsible):", &additional_info, json_mode);
             }
         }
+
+        let details = StallDetails {
+            length_ms: stall.duration.as_secs_f64() * 1000.0,
+            relevant_traces,
+            other_traces,
+            cmdline,
+            rate_limited,
+            thread_state: stall.thread_state,
+            io_delta: stall.io_delta.as_ref().map(IoDelta::from),
+        };
+        log_with_details(
+            Severity::Info,
+            &format!("stall details for {}", stall.name),
+            &additional_info,
+            Some(&details),
+            json_mode,
+        );
     }
     Ok(())
 }
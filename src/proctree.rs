@@ -0,0 +1,115 @@
+//! Discovering the descendants of a process, for `--follow-children`.
+//!
+//! Linux-only for now: this walks `/proc` directly via `parse_ppid`/`discover_descendants`
+//! below. There's no macOS or Windows backend yet -- contrast with `thread_state`, which
+//! does have a real macOS implementation via `proc_pidinfo`. `discover_descendants` just
+//! returns an error on other platforms, which `main.rs` turns into a one-time warning
+//! rather than a hard failure.
+
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+
+/// Parses the PPID out of the contents of a `/proc/<pid>/stat` file.
+///
+/// Format is "<pid> (<comm>) <state> <ppid> ...". `comm` can itself contain spaces or
+/// parens, so we find the *last* ')' before splitting the rest on whitespace.
+fn parse_ppid(stat_contents: &str) -> Option<u32> {
+    stat_contents
+        .rfind(')')
+        .map(|idx| &stat_contents[idx + 1..])?
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u32>().ok())
+}
+
+/// Depth-first walk over a parent -> children adjacency map, collecting every descendant of
+/// `root` (not including `root` itself), in no particular order.
+fn descendants_of(root: u32, children_of: &HashMap<u32, Vec<u32>>) -> Vec<u32> {
+    let mut descendants = Vec::new();
+    let mut seen = HashSet::new();
+    let mut frontier = vec![root];
+    while let Some(pid) = frontier.pop() {
+        for &child in children_of.get(&pid).map(Vec::as_slice).unwrap_or_default() {
+            if seen.insert(child) {
+                descendants.push(child);
+                frontier.push(child);
+            }
+        }
+    }
+    descendants
+}
+
+/// Returns every live descendant (children, grandchildren, ...) of `root`, in no
+/// particular order. Best-effort: processes that exit while we're enumerating them
+/// are just skipped rather than causing an error.
+#[cfg(target_os = "linux")]
+pub fn discover_descendants(root: u32) -> Result<Vec<u32>> {
+    let mut children_of: HashMap<u32, Vec<u32>> = HashMap::new();
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) else {
+            continue;
+        };
+        let Ok(stat) = std::fs::read_to_string(format!("/proc/{pid}/stat")) else {
+            continue; // process exited between the readdir and now
+        };
+        let Some(ppid) = parse_ppid(&stat) else {
+            continue;
+        };
+        children_of.entry(ppid).or_default().push(pid);
+    }
+    Ok(descendants_of(root, &children_of))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn discover_descendants(_root: u32) -> Result<Vec<u32>> {
+    anyhow::bail!("--follow-children is currently only implemented on Linux")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ppid_handles_plain_comm() {
+        assert_eq!(parse_ppid("123 (python) S 1 123 123 0 -1 ..."), Some(1));
+    }
+
+    #[test]
+    fn parse_ppid_handles_comm_with_spaces_and_parens() {
+        assert_eq!(
+            parse_ppid("123 (my (weird) proc) S 42 123 123 0 -1 ..."),
+            Some(42)
+        );
+    }
+
+    #[test]
+    fn parse_ppid_rejects_malformed_input() {
+        assert_eq!(parse_ppid("garbage"), None);
+        assert_eq!(parse_ppid("123 (comm) S"), None);
+    }
+
+    #[test]
+    fn descendants_of_collects_whole_subtree() {
+        let mut children_of = HashMap::new();
+        children_of.insert(1, vec![2, 3]);
+        children_of.insert(2, vec![4]);
+        children_of.insert(3, vec![5]);
+        let mut result = descendants_of(1, &children_of);
+        result.sort();
+        assert_eq!(result, vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn descendants_of_ignores_unrelated_branches() {
+        let mut children_of = HashMap::new();
+        children_of.insert(1, vec![2]);
+        children_of.insert(99, vec![100]);
+        assert_eq!(descendants_of(1, &children_of), vec![2]);
+    }
+
+    #[test]
+    fn descendants_of_empty_tree_is_empty() {
+        assert!(descendants_of(1, &HashMap::new()).is_empty());
+    }
+}
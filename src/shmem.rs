@@ -12,42 +12,98 @@ use std::{
     time::{Duration, Instant},
 };
 
+use crate::telemetry;
+
 // Just output from secrets.token_bytes(16)
 const MAGIC: &[u8; 16] = b"\xad\xceat\x17I\xffA\xe8\xd4\xe8\nP\xb1\xfc\x86";
-const VERSION: usize = 0;
+// v1: ThreadHint grew a second field to distinguish per-interpreter GIL slots from
+// the process-global one.
+const VERSION: usize = 1;
 
 static PAGE_SIZE: Lazy<usize> = Lazy::new(get_page_size);
 
-#[derive(Pod, Zeroable, Clone, Copy, Debug)]
-#[repr(transparent)]
-pub struct ThreadHint(usize);
+const KIND_GIL: usize = 0;
+const KIND_THREAD: usize = 1;
+const KIND_INTERPRETER_GIL: usize = 2;
+
+/// What a `StallTracker` slot is tracking: the process-global GIL, a specific OS
+/// thread, or the GIL of one particular sub-interpreter (CPython 3.12+).
+#[derive(Pod, Zeroable, Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct ThreadHint {
+    kind: usize,
+    value: usize,
+}
+
+pub const GIL: ThreadHint = ThreadHint {
+    kind: KIND_GIL,
+    value: 0,
+};
 
-pub const GIL: ThreadHint = ThreadHint(0);
+/// The result of `ThreadHint::describe` -- a friendlier view of what a hint tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadHintKind {
+    Gil,
+    Thread(usize),
+    InterpreterGil(usize),
+}
 
 impl ThreadHint {
     pub fn from_thread_id(id: usize) -> Result<Self> {
         if id == 0 {
             bail!("thread id must be non-zero");
         }
-        Ok(ThreadHint(id))
+        Ok(ThreadHint {
+            kind: KIND_THREAD,
+            value: id,
+        })
+    }
+
+    /// A GIL hint scoped to one sub-interpreter, identified by its interpreter id
+    /// (e.g. from `PyInterpreterState::get_id`). Lets applications embedding
+    /// multiple isolated interpreters detect a stalled GIL independently in each one,
+    /// instead of conflating them under a single process-wide hint.
+    pub fn for_interpreter(interp_id: usize) -> Self {
+        ThreadHint {
+            kind: KIND_INTERPRETER_GIL,
+            value: interp_id,
+        }
     }
 
     pub fn is_gil(self) -> bool {
-        self.0 == 0
+        matches!(self.kind, KIND_GIL | KIND_INTERPRETER_GIL)
+    }
+
+    pub fn interpreter_id(self) -> Option<usize> {
+        (self.kind == KIND_INTERPRETER_GIL).then_some(self.value)
     }
 
     pub fn thread_id(self) -> Result<usize> {
-        if self.is_gil() {
+        if self.kind != KIND_THREAD {
             bail!("thread hint is 'GIL', not a specific thread")
         }
-        Ok(self.0)
+        Ok(self.value)
+    }
+
+    /// A friendlier view of the two raw fields, for callers (like `list_trackers`)
+    /// that want to report a hint back out without reaching into its internals.
+    pub fn describe(self) -> ThreadHintKind {
+        match self.kind {
+            KIND_THREAD => ThreadHintKind::Thread(self.value),
+            KIND_INTERPRETER_GIL => ThreadHintKind::InterpreterGil(self.value),
+            _ => ThreadHintKind::Gil,
+        }
     }
 
     pub fn relevant(self, trace: &StackTrace) -> bool {
-        if self.is_gil() {
-            trace.owns_gil
-        } else {
-            trace.thread_id == TryInto::<u64>::try_into(self.0).unwrap()
+        match self.kind {
+            // XX TODO: py-spy's StackTrace doesn't currently report which
+            // sub-interpreter (if any) owns the GIL it's describing, so a
+            // per-interpreter hint can only narrow down to "some thread holds *a*
+            // GIL" rather than specifically *this* interpreter's. Tighten this once
+            // py-spy exposes that.
+            KIND_GIL | KIND_INTERPRETER_GIL => trace.owns_gil,
+            _ => trace.thread_id == TryInto::<u64>::try_into(self.value).unwrap(),
         }
     }
 }
@@ -73,6 +129,16 @@ pub struct StallReport {
     pub name: String,
     pub thread_hint: ThreadHint,
     pub duration: Duration,
+    /// OS scheduler state of the thread identified by `thread_hint`, if we were able
+    /// to determine one at report time. For a plain thread hint this is filled in
+    /// immediately; for `GIL`, the responsible OS thread is only known once we've
+    /// found which thread's stack trace claims to own the GIL, so callers may need to
+    /// fill this in themselves after fetching stack traces.
+    pub thread_state: Option<crate::thread_state::ThreadState>,
+    /// Resource usage accrued by the whole process between when we started tracking
+    /// this stall and when we reported it. `None` unless telemetry collection was
+    /// enabled and sampling succeeded at both ends of the window.
+    pub io_delta: Option<crate::telemetry::TelemetryDelta>,
 }
 
 #[derive(Zeroable, Debug)]
@@ -122,10 +188,19 @@ fn create_exported_slots() -> &'static mut [StallTracker] {
 
 static SLOT_FREELIST: Mutex<Option<Vec<&'static mut StallTracker>>> = Mutex::new(None);
 
+// The full exported slab, for in-process introspection (`list_slots`). Stored as a
+// raw (address, len) pair rather than a `&'static [StallTracker]`, since individual
+// slots are also handed out as exclusive `&'static mut StallTracker` via
+// `SLOT_FREELIST` -- this file already treats the slab as "one allocation, disjoint
+// owners per slot" rather than tracking it with the borrow checker.
+static ALL_SLOTS: Mutex<Option<(usize, usize)>> = Mutex::new(None);
+
 pub fn alloc_slot(name: &str, thread_hint: ThreadHint) -> Result<&'static mut StallTracker> {
     let mut guard = SLOT_FREELIST.lock().unwrap();
     if guard.is_none() {
-        *guard = Some(create_exported_slots().iter_mut().collect());
+        let slots = create_exported_slots();
+        *ALL_SLOTS.lock().unwrap() = Some((slots.as_ptr() as usize, slots.len()));
+        *guard = Some(slots.iter_mut().collect());
     }
 
     let string_to_leak = name.to_owned();
@@ -147,7 +222,8 @@ pub fn alloc_slot(name: &str, thread_hint: ThreadHint) -> Result<&'static mut St
     slot.metadata = metadata;
     // Release ordering to ensure that 'metadata' update is published before the store
     // becomes visible, to maintain the invariant that out-of-process reads should never
-    // see a Slot with odd count + invalid metadata.
+    // see a Slot with odd count + invalid metadata. This ordering is exercised
+    // exhaustively by tests/loom_stall_tracker.rs.
     slot.count.fetch_add(1, Ordering::Release);
     Ok(slot)
 }
@@ -162,20 +238,85 @@ pub fn release_slot(slot: &'static mut StallTracker) -> Result<()> {
     Ok(())
 }
 
+/// What `list_slots` reports for one currently-allocated tracker.
+pub struct SlotInfo {
+    pub name: String,
+    pub thread_hint: ThreadHint,
+    pub is_active: bool,
+    pub count: u64,
+    pub counter_address: usize,
+}
+
+/// Snapshot every currently-allocated tracker slot, for in-process introspection
+/// (e.g. a `/debug` endpoint, or logging a summary at SIGQUIT) without going through
+/// the out-of-process watchdog.
+pub fn list_slots() -> Vec<SlotInfo> {
+    let Some((base_addr, len)) = *ALL_SLOTS.lock().unwrap() else {
+        return Vec::new();
+    };
+    let free_addrs: std::collections::HashSet<usize> = SLOT_FREELIST
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|freelist| {
+            freelist
+                .iter()
+                .map(|slot| *slot as *const StallTracker as usize)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // Safety: this slab was leaked for 'static in create_exported_slots, and we only
+    // ever hand out disjoint slots from it (see the ALL_SLOTS comment above), so
+    // reading it here doesn't alias any `&mut` a caller could currently be holding.
+    let slots = unsafe { std::slice::from_raw_parts(base_addr as *const StallTracker, len) };
+    slots
+        .iter()
+        .filter(|slot| {
+            let addr = *slot as *const StallTracker as usize;
+            !free_addrs.contains(&addr) && slot.count.load(Ordering::Relaxed) > 0
+        })
+        .map(|slot| {
+            let count = slot.count.load(Ordering::Relaxed);
+            let metadata = slot.metadata;
+            // Safety: name_ptr/name_len point at a string we deliberately leaked in
+            // alloc_slot, and this slot isn't in the freelist, so it's still alive.
+            let name = unsafe {
+                std::str::from_utf8_unchecked(std::slice::from_raw_parts(
+                    metadata.name_ptr as *const u8,
+                    metadata.name_len,
+                ))
+            }
+            .to_owned();
+            SlotInfo {
+                name,
+                thread_hint: metadata.thread_hint,
+                is_active: count % 2 == 1,
+                count,
+                counter_address: &slot.count as *const _ as usize,
+            }
+        })
+        .collect()
+}
+
 struct StallTrackerSnapshot {
     stall_tracker: StallTracker,
     last_updated: Instant,
+    // Only populated when telemetry collection is enabled, since sampling it costs
+    // extra syscalls on every poll where tracking (re)starts.
+    telemetry: Option<telemetry::ResourceSnapshot>,
 }
 
 pub struct PerpetuoProc {
     slots_ptr: usize,
     slots_count: usize,
     last_updates: Vec<StallTrackerSnapshot>,
+    collect_telemetry: bool,
     pub spy: py_spy::PythonSpy,
 }
 
 impl PerpetuoProc {
-    pub fn new(pid: u32, config: &py_spy::Config) -> Result<PerpetuoProc> {
+    pub fn new(pid: u32, config: &py_spy::Config, collect_telemetry: bool) -> Result<PerpetuoProc> {
         let spy = py_spy::PythonSpy::new(pid.try_into()?, config)?;
 
         let maps = proc_maps::get_process_maps(pid as proc_maps::Pid)?;
@@ -229,17 +370,22 @@ impl PerpetuoProc {
                         .process
                         .copy_vec::<StallTracker>(slots_ptr, slots_count)?;
                     let now = Instant::now();
+                    let telemetry = collect_telemetry
+                        .then(|| telemetry::sample(pid).ok())
+                        .flatten();
                     let last_updates = slots
                         .into_iter()
                         .map(|stall_tracker| StallTrackerSnapshot {
                             stall_tracker,
                             last_updated: now,
+                            telemetry,
                         })
                         .collect();
                     return Ok(PerpetuoProc {
                         slots_ptr,
                         slots_count,
                         last_updates,
+                        collect_telemetry,
                         spy,
                     });
                 }
@@ -257,6 +403,12 @@ impl PerpetuoProc {
             .spy
             .process
             .copy_vec::<StallTracker>(self.slots_ptr, self.slots_count)?;
+        // Sample once per poll and reuse for every slot, rather than once per stalled
+        // slot, so the extra syscalls stay O(1) regardless of how many trackers fire.
+        let current_telemetry = self
+            .collect_telemetry
+            .then(|| telemetry::sample(self.spy.process.pid).ok())
+            .flatten();
 
         let mut stalls = Vec::new();
 
@@ -267,18 +419,44 @@ impl PerpetuoProc {
                     == snapshot.stall_tracker.count.load(Ordering::Relaxed)
             {
                 if now.duration_since(snapshot.last_updated) >= alert_interval {
-                    // stall detected!
-                    let name = self
-                        .spy
-                        .process
-                        .copy(current.metadata.name_ptr, current.metadata.name_len)?;
-                    let name = String::from_utf8(name)?;
-                    stalls.push(StallReport {
-                        id,
-                        name,
-                        thread_hint: current.metadata.thread_hint,
-                        duration: now.duration_since(snapshot.last_updated),
-                    })
+                    // stall detected! But `current.metadata` was captured in the same
+                    // batched copy_vec as every other slot, with no guarantee it's
+                    // from the same generation as `current.count` -- the slot could
+                    // have been released and alloc_slot'ed again with fresh metadata
+                    // partway through that read. Re-read it with a proper seqlock
+                    // retry loop before trusting name_ptr/name_len/thread_hint.
+                    match self.read_metadata_consistent(id)? {
+                        Some(metadata) => {
+                            let name = self.spy.process.copy(metadata.name_ptr, metadata.name_len)?;
+                            let name = String::from_utf8(name)?;
+                            let thread_hint = metadata.thread_hint;
+                            // For a plain thread hint we already know the OS thread id, so we
+                            // can classify it right away. For GIL we don't know *which*
+                            // thread currently holds it until stack traces are fetched, so we
+                            // leave this for the caller to fill in.
+                            let thread_state = thread_hint.thread_id().ok().and_then(|tid| {
+                                crate::thread_state::read(self.spy.process.pid, tid as u64).ok()
+                            });
+                            let io_delta = snapshot
+                                .telemetry
+                                .as_ref()
+                                .zip(current_telemetry.as_ref())
+                                .map(|(earlier, latest)| latest.delta_since(earlier));
+                            stalls.push(StallReport {
+                                id,
+                                name,
+                                thread_hint,
+                                duration: now.duration_since(snapshot.last_updated),
+                                thread_state,
+                                io_delta,
+                            })
+                        }
+                        None => {
+                            // Count never stabilized (or went idle) while we were
+                            // re-reading it -- skip this slot for this poll; if it's
+                            // still genuinely stalled we'll catch it on the next one.
+                        }
+                    }
                 } else {
                     // stall in progress, but it hasn't hit our alerting threshold
                     // yet... leave the snapshot alone so we can continue tracking it.
@@ -286,10 +464,45 @@ impl PerpetuoProc {
             } else {
                 snapshot.stall_tracker = current;
                 snapshot.last_updated = now;
+                snapshot.telemetry = current_telemetry;
             }
         }
         Ok(stalls)
     }
+
+    /// Seqlock-style read of slot `id`'s metadata straight from the target process,
+    /// safe against the slot being released and `alloc_slot`-ed again mid-read.
+    ///
+    /// Mirrors the reasoning in `alloc_slot`: metadata is always fully written before
+    /// `count` is Release-incremented to odd, so if we see the same odd `count`
+    /// before and after reading metadata, that metadata must belong to the generation
+    /// `count` identifies. Retries a bounded number of times in case the slot is
+    /// being toggled in a tight loop; gives up and returns `None` (meaning: skip this
+    /// slot this poll) rather than retrying forever.
+    fn read_metadata_consistent(&self, id: usize) -> Result<Option<SlotMetadata>> {
+        const MAX_ATTEMPTS: u32 = 10;
+        let addr = self.slots_ptr + id * size_of::<StallTracker>();
+        for _ in 0..MAX_ATTEMPTS {
+            let tracker = self.spy.process.copy_struct::<StallTracker>(addr)?;
+            let c1 = tracker.count.load(Ordering::Acquire);
+            if c1 % 2 == 0 {
+                // Went idle since our snapshot-comparison poll; nothing to report.
+                return Ok(None);
+            }
+            let c2 = self
+                .spy
+                .process
+                .copy_struct::<StallTracker>(addr)?
+                .count
+                .load(Ordering::Acquire);
+            if c1 == c2 {
+                return Ok(Some(tracker.metadata));
+            }
+            // count moved between our two reads -- the slot was toggled (or
+            // released+realloc'ed) while we were reading it; retry.
+        }
+        Ok(None)
+    }
 }
 
 #[cfg(unix)]
@@ -311,3 +524,105 @@ fn get_page_size() -> usize {
 fn round_up_to_multiple(value: usize, multiple: usize) -> usize {
     (value + multiple - 1) / multiple * multiple
 }
+
+/// The OS-level id of the calling thread, in the same namespace `StackTrace::thread_id`
+/// values live in (what py-spy reports, and what `/proc/<pid>/task/<tid>` expects on
+/// Linux). Used for thread hints that mean "whichever thread registers me", like the
+/// "asyncio" alias in `ThreadHintArg::encode`, where the tracker is meant to follow
+/// the thread that creates it (typically the one running the event loop).
+#[cfg(target_os = "linux")]
+pub fn current_os_thread_id() -> usize {
+    unsafe { libc::syscall(libc::SYS_gettid) as usize }
+}
+
+#[cfg(target_os = "macos")]
+pub fn current_os_thread_id() -> usize {
+    let mut tid: u64 = 0;
+    unsafe {
+        libc::pthread_threadid_np(std::ptr::null_mut(), &mut tid);
+    }
+    tid as usize
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn current_os_thread_id() -> usize {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gil_hint_describes_as_gil() {
+        assert!(GIL.is_gil());
+        assert_eq!(GIL.interpreter_id(), None);
+        assert!(GIL.thread_id().is_err());
+        assert_eq!(GIL.describe(), ThreadHintKind::Gil);
+    }
+
+    #[test]
+    fn thread_hint_describes_as_thread() {
+        let hint = ThreadHint::from_thread_id(42).unwrap();
+        assert!(!hint.is_gil());
+        assert_eq!(hint.interpreter_id(), None);
+        assert_eq!(hint.thread_id().unwrap(), 42);
+        assert_eq!(hint.describe(), ThreadHintKind::Thread(42));
+    }
+
+    #[test]
+    fn thread_hint_rejects_zero() {
+        assert!(ThreadHint::from_thread_id(0).is_err());
+    }
+
+    #[test]
+    fn interpreter_gil_hint_describes_as_gil_and_reports_its_id() {
+        let hint = ThreadHint::for_interpreter(7);
+        assert!(hint.is_gil());
+        assert_eq!(hint.interpreter_id(), Some(7));
+        assert!(hint.thread_id().is_err());
+        assert_eq!(hint.describe(), ThreadHintKind::InterpreterGil(7));
+    }
+
+    #[test]
+    fn distinct_interpreter_gil_hints_are_not_equal() {
+        assert_ne!(ThreadHint::for_interpreter(1), ThreadHint::for_interpreter(2));
+        assert_ne!(ThreadHint::for_interpreter(1), GIL);
+    }
+
+    // alloc_slot/release_slot/list_slots all share process-global statics
+    // (SLOT_FREELIST, ALL_SLOTS), so -- unlike the rest of this module's tests --
+    // they can't run as independent, parallel #[test] fns without racing each other.
+    // One consolidated test keeps them serialized within this module; the unit tests
+    // in lib.rs still allocate slots in parallel with it, so the counts below only
+    // include slots carrying this test's own names.
+    #[test]
+    fn list_slots_reports_only_currently_allocated_slots() {
+        let ours = |slots: &[SlotInfo]| slots.iter().filter(|s| s.name.starts_with("test-slot-")).count();
+        let before = ours(&list_slots());
+
+        let a = alloc_slot("test-slot-a", ThreadHint::from_thread_id(123).unwrap()).unwrap();
+        let b = alloc_slot("test-slot-b", GIL).unwrap();
+
+        let slots = list_slots();
+        assert_eq!(ours(&slots), before + 2);
+        let a_info = slots.iter().find(|s| s.name == "test-slot-a").unwrap();
+        assert_eq!(a_info.thread_hint, ThreadHint::from_thread_id(123).unwrap());
+        assert!(a_info.is_active);
+        assert_eq!(a_info.count, 1);
+        let b_info = slots.iter().find(|s| s.name == "test-slot-b").unwrap();
+        assert_eq!(b_info.thread_hint, GIL);
+        assert!(b_info.is_active);
+
+        a.toggle(); // go idle
+        release_slot(a).unwrap();
+
+        let slots = list_slots();
+        assert_eq!(ours(&slots), before + 1);
+        assert!(slots.iter().all(|s| s.name != "test-slot-a"));
+
+        b.toggle();
+        release_slot(b).unwrap();
+        assert_eq!(ours(&list_slots()), before);
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::Result;
+use std::time::Duration;
+
+/// A point-in-time snapshot of a process's I/O and CPU counters, as read from
+/// `/proc/<pid>/io`, `/proc/<pid>/stat`, and `/proc/<pid>/statm`.
+///
+/// Sampled once when we start tracking a stall and again when we report it, so the
+/// difference shows what the process was actually doing during the stall window --
+/// e.g. "480ms stall, 12 MiB of disk reads, 2ms of CPU" points at an I/O wait, while
+/// "480ms stall, 0 bytes, 480ms of CPU" points at a busy loop.
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceSnapshot {
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub utime_ticks: u64,
+    pub stime_ticks: u64,
+    pub rss_pages: u64,
+}
+
+/// The difference between two `ResourceSnapshot`s, in human-friendly units.
+pub struct TelemetryDelta {
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub cpu_time: Duration,
+    pub rss_bytes: i64,
+}
+
+impl ResourceSnapshot {
+    pub fn delta_since(&self, earlier: &ResourceSnapshot) -> TelemetryDelta {
+        let ticks_per_sec = clock_ticks_per_sec();
+        let cpu_ticks = (self.utime_ticks + self.stime_ticks)
+            .saturating_sub(earlier.utime_ticks + earlier.stime_ticks);
+        TelemetryDelta {
+            read_bytes: self.read_bytes.saturating_sub(earlier.read_bytes),
+            write_bytes: self.write_bytes.saturating_sub(earlier.write_bytes),
+            cpu_time: Duration::from_secs_f64(cpu_ticks as f64 / ticks_per_sec as f64),
+            rss_bytes: (self.rss_pages as i64 - earlier.rss_pages as i64) * page_size() as i64,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn sample(pid: u32) -> Result<ResourceSnapshot> {
+    let (mut read_bytes, mut write_bytes) = (0, 0);
+    for line in std::fs::read_to_string(format!("/proc/{pid}/io"))?.lines() {
+        if let Some(value) = line.strip_prefix("read_bytes:") {
+            read_bytes = value.trim().parse().unwrap_or(0);
+        } else if let Some(value) = line.strip_prefix("write_bytes:") {
+            write_bytes = value.trim().parse().unwrap_or(0);
+        }
+    }
+
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat"))?;
+    // Fields are "<pid> (<comm>) <state> ...", and utime/stime are fields 14 and 15
+    // (1-indexed) of the whole line, so field index 11 and 12 (0-indexed) counting
+    // from the state field right after the comm.
+    let after_comm = stat
+        .rfind(')')
+        .map(|idx| &stat[idx + 1..])
+        .ok_or_else(|| anyhow::anyhow!("unexpected format in /proc/{pid}/stat"))?;
+    let fields: Vec<&str> = after_comm.split_whitespace().collect();
+    let utime_ticks = fields.get(11).and_then(|s| s.parse().ok()).unwrap_or(0);
+    let stime_ticks = fields.get(12).and_then(|s| s.parse().ok()).unwrap_or(0);
+
+    let statm = std::fs::read_to_string(format!("/proc/{pid}/statm"))?;
+    let rss_pages = statm
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+
+    Ok(ResourceSnapshot {
+        read_bytes,
+        write_bytes,
+        utime_ticks,
+        stime_ticks,
+        rss_pages,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sample(_pid: u32) -> Result<ResourceSnapshot> {
+    anyhow::bail!("I/O and CPU telemetry is currently only implemented on Linux")
+}
+
+#[cfg(target_os = "linux")]
+fn clock_ticks_per_sec() -> i64 {
+    unsafe { libc::sysconf(libc::_SC_CLK_TCK) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clock_ticks_per_sec() -> i64 {
+    100
+}
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[cfg(not(unix))]
+fn page_size() -> usize {
+    4096
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(read_bytes: u64, write_bytes: u64, utime_ticks: u64, stime_ticks: u64, rss_pages: u64) -> ResourceSnapshot {
+        ResourceSnapshot {
+            read_bytes,
+            write_bytes,
+            utime_ticks,
+            stime_ticks,
+            rss_pages,
+        }
+    }
+
+    #[test]
+    fn delta_since_computes_human_friendly_units() {
+        let earlier = snapshot(100, 50, 10, 5, 1000);
+        let later = snapshot(150, 80, 20, 15, 1200);
+        let delta = later.delta_since(&earlier);
+        assert_eq!(delta.read_bytes, 50);
+        assert_eq!(delta.write_bytes, 30);
+        assert_eq!(delta.cpu_time, Duration::from_secs_f64(20.0 / clock_ticks_per_sec() as f64));
+        assert_eq!(delta.rss_bytes, 200 * page_size() as i64);
+    }
+
+    #[test]
+    fn delta_since_saturates_instead_of_underflowing() {
+        // A later snapshot with smaller counters than the earlier one shouldn't
+        // panic or wrap -- this can happen in practice if counters reset (e.g. the
+        // process we're watching got replaced via exec()).
+        let earlier = snapshot(100, 100, 10, 10, 1000);
+        let later = snapshot(0, 0, 0, 0, 1000);
+        let delta = later.delta_since(&earlier);
+        assert_eq!(delta.read_bytes, 0);
+        assert_eq!(delta.write_bytes, 0);
+        assert_eq!(delta.cpu_time, Duration::ZERO);
+        assert_eq!(delta.rss_bytes, 0);
+    }
+
+    #[test]
+    fn delta_since_reports_negative_rss_shrink() {
+        let earlier = snapshot(0, 0, 0, 0, 1000);
+        let later = snapshot(0, 0, 0, 0, 800);
+        let delta = later.delta_since(&earlier);
+        assert_eq!(delta.rss_bytes, -200 * page_size() as i64);
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+/// The OS scheduler state of a single thread, as reported by the kernel.
+///
+/// This is orthogonal to whatever perpetuo's own stall tracker thinks: a thread can be
+/// "stalled" (not toggling its tracker) while sitting in any of these states. The
+/// distinction matters most for the GIL-relevant thread: `UninterruptibleDiskSleep`
+/// means the GIL holder is blocked in a syscall, while `Running` means it's spinning.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ThreadState {
+    Running,
+    Sleeping,
+    UninterruptibleDiskSleep,
+    Stopped,
+    Tracing,
+    Zombie,
+    Idle,
+    Unknown(char),
+}
+
+impl ThreadState {
+    fn from_linux_char(c: char) -> ThreadState {
+        match c {
+            'R' => ThreadState::Running,
+            'S' => ThreadState::Sleeping,
+            'D' => ThreadState::UninterruptibleDiskSleep,
+            'T' => ThreadState::Stopped,
+            't' => ThreadState::Tracing,
+            'Z' => ThreadState::Zombie,
+            'I' => ThreadState::Idle,
+            other => ThreadState::Unknown(other),
+        }
+    }
+}
+
+/// Read the current scheduler state of a single thread.
+///
+/// `pid` is the process id and `tid` is the OS-level thread id, e.g. as found in
+/// `StackTrace::thread_id`.
+#[cfg(target_os = "linux")]
+pub fn read(pid: u32, tid: u64) -> Result<ThreadState> {
+    let path = format!("/proc/{pid}/task/{tid}/stat");
+    let contents = std::fs::read_to_string(&path)?;
+    // Format is "<tid> (<comm>) <state> ...". comm can itself contain spaces or
+    // parentheses, so find the *last* ')' before splitting the rest on whitespace.
+    let state_char = contents
+        .rfind(')')
+        .and_then(|paren| contents[paren + 1..].split_whitespace().next())
+        .and_then(|field| field.chars().next())
+        .ok_or_else(|| anyhow::anyhow!("unexpected format in {path}"))?;
+    Ok(ThreadState::from_linux_char(state_char))
+}
+
+/// Read the current scheduler state of a single thread.
+///
+/// `pid` is the process id and `tid` is the Mach thread port, e.g. as found in
+/// `StackTrace::thread_id`.
+#[cfg(target_os = "macos")]
+pub fn read(pid: u32, tid: u64) -> Result<ThreadState> {
+    // macOS has no /proc, so we go through proc_pidinfo(PROC_PIDTHREADINFO) instead,
+    // which fills in a proc_threadinfo describing the thread's Mach run state.
+    #[repr(C)]
+    struct ProcThreadInfo {
+        pth_user_time: u64,
+        pth_system_time: u64,
+        pth_cpu_usage: i32,
+        pth_policy: i32,
+        pth_run_state: i32,
+        pth_flags: i32,
+        pth_sleep_time: i32,
+        pth_curpri: i32,
+        pth_priority: i32,
+        pth_maxpriority: i32,
+        pth_name: [libc::c_char; 64],
+    }
+
+    const PROC_PIDTHREADINFO: libc::c_int = 5;
+    // From <mach/thread_info.h>
+    const TH_STATE_RUNNING: i32 = 1;
+    const TH_STATE_STOPPED: i32 = 2;
+    const TH_STATE_WAITING: i32 = 3;
+    const TH_STATE_UNINTERRUPTIBLE: i32 = 4;
+    const TH_STATE_HALTED: i32 = 5;
+
+    extern "C" {
+        fn proc_pidinfo(
+            pid: libc::c_int,
+            flavor: libc::c_int,
+            arg: u64,
+            buffer: *mut libc::c_void,
+            buffersize: libc::c_int,
+        ) -> libc::c_int;
+    }
+
+    let size = std::mem::size_of::<ProcThreadInfo>() as libc::c_int;
+    let mut info: ProcThreadInfo = unsafe { std::mem::zeroed() };
+    let ret = unsafe {
+        proc_pidinfo(
+            pid as libc::c_int,
+            PROC_PIDTHREADINFO,
+            tid,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    if ret != size {
+        bail!("proc_pidinfo(PROC_PIDTHREADINFO) failed for pid {pid} thread {tid}");
+    }
+    Ok(match info.pth_run_state {
+        TH_STATE_RUNNING => ThreadState::Running,
+        TH_STATE_WAITING => ThreadState::Sleeping,
+        TH_STATE_UNINTERRUPTIBLE => ThreadState::UninterruptibleDiskSleep,
+        TH_STATE_STOPPED | TH_STATE_HALTED => ThreadState::Stopped,
+        other => ThreadState::Unknown(char::from_u32(other as u32).unwrap_or('?')),
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn read(_pid: u32, _tid: u64) -> Result<ThreadState> {
+    bail!("thread state classification is not implemented on this platform")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_linux_char_covers_documented_states() {
+        assert_eq!(ThreadState::from_linux_char('R'), ThreadState::Running);
+        assert_eq!(ThreadState::from_linux_char('S'), ThreadState::Sleeping);
+        assert_eq!(
+            ThreadState::from_linux_char('D'),
+            ThreadState::UninterruptibleDiskSleep
+        );
+        assert_eq!(ThreadState::from_linux_char('T'), ThreadState::Stopped);
+        assert_eq!(ThreadState::from_linux_char('t'), ThreadState::Tracing);
+        assert_eq!(ThreadState::from_linux_char('Z'), ThreadState::Zombie);
+        assert_eq!(ThreadState::from_linux_char('I'), ThreadState::Idle);
+    }
+
+    #[test]
+    fn from_linux_char_falls_back_to_unknown() {
+        assert_eq!(ThreadState::from_linux_char('X'), ThreadState::Unknown('X'));
+    }
+}